@@ -0,0 +1,213 @@
+//! KDE Window decoration manager
+//!
+//! This is the legacy `org_kde_kwin_server_decoration` protocol. It predates
+//! `zxdg_decoration_manager_v1` and is still the only decoration negotiation
+//! protocol a number of GTK/Qt/SDL clients bind, so compositors generally want
+//! to advertise both.
+//!
+//! Unlike the xdg-decoration protocol this one has no configure/ack_configure
+//! cycle: the client requests a mode and the compositor replies with a `mode`
+//! event. This manager sends one right after the decoration object is created
+//! and one in reply to each `request_mode`; a mode the compositor changes later
+//! on its own is not pushed over the KDE interface.
+//!
+//! If a surface already negotiates its mode through
+//! `zxdg_toplevel_decoration_v1`, that protocol is authoritative: this
+//! manager only ever reports its outcome back over the KDE interface instead
+//! of negotiating a second, possibly conflicting mode.
+//!
+//! The compositor decides the real outcome the same way it does for
+//! `zxdg_toplevel_decoration_v1`: `RequestMode` only reports the client's
+//! preference, the compositor enforces a mode (possibly a different one) by
+//! calling [`ToplevelSurface::set_decoration_mode`] on its own record of the
+//! toplevel, and the `mode` event we send back reflects whatever ends up
+//! pending, not necessarily what the client asked for.
+//!
+//! ```no_run
+//! # extern crate wayland_server;
+//! #
+//! use smithay::wayland::shell::kde_decoration::{init_kde_decoration_manager, KdeDecorationRequest};
+//! use smithay::reexports::wayland_protocols::misc::server_decoration::server::org_kde_kwin_server_decoration::Mode as KdeMode;
+//! use smithay::reexports::wayland_protocols::unstable::xdg_decoration::v1::server::zxdg_toplevel_decoration_v1::Mode;
+//!
+//! # let mut display = wayland_server::Display::new();
+//! # let toplevel_for_surface = |_: &_| -> Option<smithay::wayland::shell::xdg::ToplevelSurface> { None };
+//!
+//! init_kde_decoration_manager(
+//!     &mut display,
+//!     KdeMode::Server,
+//!     move |req, _ddata| match req {
+//!         KdeDecorationRequest::RequestMode { surface, .. } => {
+//!             // Always enforce server-side decorations, regardless of what
+//!             // the client asked for.
+//!             if let Some(toplevel) = toplevel_for_surface(&surface) {
+//!                 toplevel.set_decoration_mode(Mode::ServerSide);
+//!             }
+//!         }
+//!     },
+//!     None,
+//! );
+//!
+
+use std::{cell::RefCell, rc::Rc};
+
+use wayland_protocols::misc::server_decoration::server::{
+    org_kde_kwin_server_decoration::{self, OrgKdeKwinServerDecoration},
+    org_kde_kwin_server_decoration_manager::{self, OrgKdeKwinServerDecorationManager},
+};
+use wayland_protocols::unstable::xdg_decoration::v1::server::zxdg_toplevel_decoration_v1::Mode;
+use wayland_server::{protocol::wl_surface::WlSurface, DispatchData, Display, Filter, Global, Main};
+
+use super::xdg::xdg_handlers::ShellSurfaceUserData;
+
+/// Events generated by the KDE decoration manager
+#[derive(Debug)]
+pub enum KdeDecorationRequest {
+    /// The client asked for the given decoration mode on this surface.
+    ///
+    /// Not forwarded if the surface already has an active
+    /// `zxdg_toplevel_decoration_v1`: in that case the xdg-decoration
+    /// negotiation is authoritative and this object is only kept in sync
+    /// with it.
+    RequestMode {
+        /// The surface the decoration object is associated with
+        surface: WlSurface,
+        /// The requested decoration mode
+        mode: Mode,
+    },
+}
+
+fn to_xdg_mode(mode: org_kde_kwin_server_decoration::Mode) -> Mode {
+    match mode {
+        org_kde_kwin_server_decoration::Mode::Server => Mode::ServerSide,
+        _ => Mode::ClientSide,
+    }
+}
+
+fn to_kde_mode(mode: Mode) -> org_kde_kwin_server_decoration::Mode {
+    match mode {
+        Mode::ClientSide => org_kde_kwin_server_decoration::Mode::Client,
+        Mode::ServerSide => org_kde_kwin_server_decoration::Mode::Server,
+        _ => org_kde_kwin_server_decoration::Mode::Client,
+    }
+}
+
+/// Resolves the KDE mode to advertise for a surface: whatever xdg-decoration
+/// negotiated for it, translated to its KDE equivalent, or the compositor's
+/// `default_mode` (including its `None`, truly undecorated variant, which
+/// `zxdg_toplevel_decoration_v1::Mode` has no way to express) if xdg-decoration
+/// isn't in play.
+fn resolved_kde_mode(
+    xdg_mode: Option<Mode>,
+    default_mode: org_kde_kwin_server_decoration::Mode,
+) -> org_kde_kwin_server_decoration::Mode {
+    xdg_mode.map(to_kde_mode).unwrap_or(default_mode)
+}
+
+/// Returns the toplevel's negotiated decoration mode (pending, or else the
+/// last acked one), regardless of which decoration protocol drove it there.
+///
+/// Pending is checked first so a decision just made (e.g. a `RequestMode`
+/// callback calling `set_decoration_mode` to override the client) is
+/// reflected immediately, instead of being shadowed by a stale acked mode
+/// from an earlier configure.
+fn pending_decoration_mode(data: &ShellSurfaceUserData) -> Option<Mode> {
+    data.pending.borrow().decoration_mode.or(data.current.borrow().decoration_mode)
+}
+
+/// Returns the mode negotiated through `zxdg_toplevel_decoration_v1` for
+/// this surface, if it has an active decoration object of that kind.
+fn active_xdg_mode(data: &ShellSurfaceUserData) -> Option<Mode> {
+    if data.decoration.borrow().is_none() {
+        return None;
+    }
+    pending_decoration_mode(data)
+}
+
+/// Create a new KDE server-side decoration manager global.
+///
+/// `default_mode` is advertised to every client as the compositor's default
+/// on bind, as the protocol's `default_mode` event requires. It is the raw
+/// KDE mode rather than [`Mode`] because this protocol, unlike
+/// xdg-decoration, also has a `None` mode (truly undecorated) that `Mode`
+/// cannot express.
+pub fn init_kde_decoration_manager<L, Impl>(
+    display: &mut Display,
+    default_mode: org_kde_kwin_server_decoration::Mode,
+    implementation: Impl,
+    _logger: L,
+) -> Global<OrgKdeKwinServerDecorationManager>
+where
+    L: Into<Option<::slog::Logger>>,
+    Impl: FnMut(KdeDecorationRequest, DispatchData<'_>) + 'static,
+{
+    let cb = Rc::new(RefCell::new(implementation));
+    display.create_global(
+        1,
+        Filter::new(
+            move |(manager, _version): (Main<OrgKdeKwinServerDecorationManager>, _), _, _| {
+                manager.default_mode(default_mode as u32);
+
+                let cb = cb.clone();
+                manager.quick_assign(move |_manager, request, ddata| match request {
+                    org_kde_kwin_server_decoration_manager::Request::Create { id, surface } => {
+                        let xdg_mode = surface
+                            .as_ref()
+                            .user_data()
+                            .get::<ShellSurfaceUserData>()
+                            .and_then(active_xdg_mode);
+                        id.mode(resolved_kde_mode(xdg_mode, default_mode));
+
+                        let cb = cb.clone();
+                        let surface = surface.clone();
+                        id.quick_assign(move |decoration, request, ddata| {
+                            let mode = match request {
+                                org_kde_kwin_server_decoration::Request::RequestMode { mode } => mode,
+                                _ => return,
+                            };
+
+                            if let Some(data) = surface.as_ref().user_data().get::<ShellSurfaceUserData>() {
+                                if data.decoration.borrow().is_some() {
+                                    // xdg-decoration owns this surface's mode: don't
+                                    // negotiate a second, possibly conflicting one.
+                                    decoration.mode(resolved_kde_mode(active_xdg_mode(data), default_mode));
+                                    return;
+                                }
+                            }
+
+                            (&mut *cb.borrow_mut())(
+                                KdeDecorationRequest::RequestMode {
+                                    surface: surface.clone(),
+                                    mode: to_xdg_mode(mode),
+                                },
+                                ddata,
+                            );
+
+                            // The callback above is the only thing that gets
+                            // to decide the real mode (typically by calling
+                            // `ToplevelSurface::set_decoration_mode` on its
+                            // own record of this surface's toplevel), so
+                            // reflect back whatever that left pending rather
+                            // than the client's raw request.
+                            let xdg_mode = surface
+                                .as_ref()
+                                .user_data()
+                                .get::<ShellSurfaceUserData>()
+                                .and_then(pending_decoration_mode);
+
+                            decoration.mode(resolved_kde_mode(xdg_mode, default_mode));
+                        });
+
+                        id.assign_destructor(Filter::new(
+                            |_decoration: OrgKdeKwinServerDecoration, _, _| {},
+                        ));
+                    }
+                    org_kde_kwin_server_decoration_manager::Request::Destroy => {
+                        // All is handled by destructor.
+                    }
+                    _ => unreachable!(),
+                });
+            },
+        ),
+    )
+}
@@ -17,10 +17,8 @@
 //!     &mut display,
 //!     |req, _ddata| match req {
 //!         XdgDecorationRequest::NewToplevelDecoration { toplevel } => {
-//!             let res = toplevel.with_pending_state(|state| {
-//!                   // Advertise server side decoration
-//!                 state.decoration_mode = Some(Mode::ServerSide);
-//!             });
+//!             // Advertise server side decoration
+//!             let res = toplevel.with_pending_decoration(|mode| *mode = Some(Mode::ServerSide));
 //!
 //!             if res.is_ok() {
 //!                 toplevel.send_configure();
@@ -43,6 +41,25 @@ use wayland_server::{DispatchData, Display, Filter, Global, Main};
 use super::ToplevelSurface;
 use crate::wayland::shell::xdg::xdg_handlers::ShellSurfaceUserData;
 
+/// Tracks what a client has told us about a `zxdg_toplevel_decoration_v1`.
+///
+/// The negotiated mode itself (scheduled and acknowledged) is not kept here:
+/// it lives in the toplevel's own pending/current state (see
+/// [`ToplevelSurface::with_pending_state`] and
+/// [`ToplevelSurface::current_decoration_mode`]) so it commits atomically
+/// with the rest of the surface's configure/ack_configure cycle. This struct
+/// only tracks bookkeeping that is local to the decoration object itself.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DecorationState {
+    /// The mode last requested by the client via `set_mode`.
+    ///
+    /// Reset to `None` when the client sends `unset_mode`.
+    pub requested_mode: Option<Mode>,
+    /// The last mode actually emitted in a `configure` for this decoration
+    /// object, used to avoid sending redundant configures.
+    pub(crate) last_sent_mode: Option<Mode>,
+}
+
 /// Events generated by xdg decoration manager
 #[derive(Debug)]
 pub enum XdgDecorationRequest {
@@ -87,11 +104,15 @@ where
                             // All is handled by destructor.
                         }
                         zxdg_decoration_manager_v1::Request::GetToplevelDecoration { id, toplevel } => {
+                            id.as_ref()
+                                .user_data()
+                                .set(|| RefCell::new(DecorationState::default()));
+
                             if let Some(data) = toplevel.as_ref().user_data().get::<ShellSurfaceUserData>() {
                                 if data.decoration.borrow().is_none() {
                                     *data.decoration.borrow_mut() = Some(id.deref().clone());
                                 } else {
-                                    use wayland_protocols::unstable::xdg_decoration::v1::server::zxdg_toplevel_decoration_v1::Error; 
+                                    use wayland_protocols::unstable::xdg_decoration::v1::server::zxdg_toplevel_decoration_v1::Error;
                                     id.as_ref().post_error(Error::AlreadyConstructed as u32, "toplevel decoration is already constructed".to_string());
                                 }
 
@@ -108,8 +129,14 @@ where
                                 );
 
                                 let cb = cb.clone();
-                                id.quick_assign(move |_, request, ddata| match request {
+                                id.quick_assign(move |decoration, request, ddata| match request {
                                     zxdg_toplevel_decoration_v1::Request::SetMode { mode } => {
+                                        if let Some(state) =
+                                            decoration.as_ref().user_data().get::<RefCell<DecorationState>>()
+                                        {
+                                            state.borrow_mut().requested_mode = Some(mode);
+                                        }
+
                                         (&mut *cb.borrow_mut())(
                                             XdgDecorationRequest::SetMode {
                                                 toplevel: toplevel.clone(),
@@ -119,6 +146,12 @@ where
                                         );
                                     }
                                     zxdg_toplevel_decoration_v1::Request::UnsetMode => {
+                                        if let Some(state) =
+                                            decoration.as_ref().user_data().get::<RefCell<DecorationState>>()
+                                        {
+                                            state.borrow_mut().requested_mode = None;
+                                        }
+
                                         (&mut *cb.borrow_mut())(
                                             XdgDecorationRequest::UnsetMode {
                                                 toplevel: toplevel.clone(),
@@ -148,6 +181,99 @@ where
     )
 }
 
+/// Returns whether a `zxdg_toplevel_decoration_v1.configure` for `mode`
+/// should actually be sent, given the mode last sent on that object.
+///
+/// Clients whose decoration preference depends on toplevel state may re-send
+/// `set_mode` in reaction to every configure we emit; echoing the same mode
+/// back every time would create an infinite configure loop, so this returns
+/// `false` once the mode hasn't changed since the last configure we sent,
+/// regardless of whether that mode came from the client's request or was
+/// enforced by the compositor.
+pub(crate) fn should_send_decoration_configure(last_sent_mode: Option<Mode>, mode: Mode) -> bool {
+    last_sent_mode != Some(mode)
+}
+
+/// Sends a `zxdg_toplevel_decoration_v1.configure`, unconditionally.
+///
+/// Callers driving the xdg_surface configure/commit cycle (see
+/// `xdg_handlers::ToplevelSurface::send_configure`) are expected to have
+/// already checked [`should_send_decoration_configure`].
 pub(super) fn send_decoration_configure(id: &ZxdgToplevelDecorationV1, mode: Mode) {
+    if let Some(state) = id.as_ref().user_data().get::<RefCell<DecorationState>>() {
+        state.borrow_mut().last_sent_mode = Some(mode);
+    }
     id.configure(mode)
-}
\ No newline at end of file
+}
+
+impl ToplevelSurface {
+    /// Schedules sending `mode` to the client as the negotiated decoration mode.
+    ///
+    /// The mode is not sent right away: it is recorded as the pending
+    /// decoration mode and folded into the next `xdg_surface.configure` (see
+    /// [`ToplevelSurface::send_configure`]), which prepends the matching
+    /// `zxdg_toplevel_decoration_v1.configure` under the same serial instead
+    /// of emitting it out of band. The mode only becomes
+    /// [`ToplevelSurface::current_decoration_mode`] once the client acks
+    /// that configure.
+    pub fn set_decoration_mode(&self, mode: Mode) {
+        self.schedule_decoration_mode(Some(mode));
+    }
+
+    /// Clears the pending decoration mode and sends an `xdg_surface.configure`.
+    ///
+    /// No `zxdg_toplevel_decoration_v1.configure` is sent for this, so the
+    /// client is not told of any change and keeps the last mode it was sent.
+    pub fn unset_decoration_mode(&self) {
+        self.schedule_decoration_mode(None);
+    }
+
+    fn schedule_decoration_mode(&self, mode: Option<Mode>) {
+        if self.with_pending_decoration(|pending| *pending = mode).is_err() {
+            return;
+        }
+
+        if let Some(mode) = mode {
+            if !self.decoration_configure_pending(mode) {
+                // No decoration configure would go out for this mode (it
+                // matches the last one sent, or there is no xdg decoration
+                // object): don't trigger a configure purely for it. Any configure
+                // still owed for other pending state is unaffected, since
+                // that caller will reach `send_configure` on its own.
+                return;
+            }
+        }
+
+        self.send_configure();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_configure_is_always_sent() {
+        assert!(should_send_decoration_configure(None, Mode::ServerSide));
+    }
+
+    #[test]
+    fn repeating_the_same_mode_is_suppressed() {
+        assert!(!should_send_decoration_configure(
+            Some(Mode::ServerSide),
+            Mode::ServerSide
+        ));
+        assert!(!should_send_decoration_configure(
+            Some(Mode::ClientSide),
+            Mode::ClientSide
+        ));
+    }
+
+    #[test]
+    fn a_changed_mode_is_still_sent() {
+        assert!(should_send_decoration_configure(
+            Some(Mode::ClientSide),
+            Mode::ServerSide
+        ));
+    }
+}
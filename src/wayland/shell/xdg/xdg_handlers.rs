@@ -0,0 +1,168 @@
+//! Shared toplevel state backing the `xdg_surface`/`xdg_toplevel` configure
+//! and commit cycle.
+//!
+//! Everything an `xdg_toplevel` negotiates with the client through a
+//! configure — its size, its `xdg_toplevel::State`s, and (via the
+//! decoration protocols) its decoration mode — is staged here as
+//! [`PendingState`] and only takes effect once the client acks the configure
+//! that carried it, so all of it commits atomically together.
+
+use std::cell::RefCell;
+
+use wayland_protocols::unstable::xdg_decoration::v1::server::zxdg_toplevel_decoration_v1::{
+    Mode, ZxdgToplevelDecorationV1,
+};
+use wayland_protocols::xdg_shell::server::xdg_surface::XdgSurface;
+use wayland_protocols::xdg_shell::server::xdg_toplevel::State;
+use wayland_server::protocol::wl_surface::WlSurface;
+
+use super::decoration::{send_decoration_configure, should_send_decoration_configure, DecorationState};
+use super::ToplevelSurface;
+
+/// Toplevel state queued for the next configure, or already acknowledged.
+#[derive(Debug, Default, Clone)]
+pub struct PendingState {
+    /// The size to suggest to the client, or `None` to let it choose.
+    pub size: Option<(i32, i32)>,
+    /// The `xdg_toplevel::State`s (maximized, fullscreen, activated, ...) to
+    /// advertise.
+    pub states: Vec<State>,
+    /// The decoration mode to negotiate, set through
+    /// [`crate::wayland::shell::xdg::decoration`].
+    pub decoration_mode: Option<Mode>,
+}
+
+/// A configure that was sent to the client and is awaiting `ack_configure`,
+/// together with the state it carried.
+#[derive(Debug, Clone)]
+struct Configure {
+    serial: u32,
+    state: PendingState,
+}
+
+/// Per-toplevel bookkeeping shared by the xdg-shell and decoration protocols.
+pub(crate) struct ShellSurfaceUserData {
+    pub(crate) wl_surface: WlSurface,
+    /// The `xdg_surface` configures are sent and acked on.
+    pub(crate) xdg_surface: XdgSurface,
+    /// The active `zxdg_toplevel_decoration_v1` for this toplevel, if any.
+    pub(crate) decoration: RefCell<Option<ZxdgToplevelDecorationV1>>,
+    /// State queued for the next configure.
+    pub(crate) pending: RefCell<PendingState>,
+    /// State acknowledged by the client at the last `ack_configure`.
+    pub(crate) current: RefCell<PendingState>,
+    /// Configures sent but not yet acked, oldest first.
+    configures: RefCell<Vec<Configure>>,
+    last_serial: RefCell<u32>,
+}
+
+impl ToplevelSurface {
+    fn shell_data(&self) -> Option<&ShellSurfaceUserData> {
+        self.shell_surface.as_ref().user_data().get::<ShellSurfaceUserData>()
+    }
+
+    /// Gives mutable access to the state queued for this toplevel's next
+    /// configure.
+    pub fn with_pending_state<F, T>(&self, f: F) -> Result<T, ()>
+    where
+        F: FnOnce(&mut PendingState) -> T,
+    {
+        let data = self.shell_data().ok_or(())?;
+        Ok(f(&mut data.pending.borrow_mut()))
+    }
+
+    /// Gives mutable access to just the decoration mode queued for this
+    /// toplevel's next configure, for callers that only care about that part
+    /// of [`PendingState`].
+    pub fn with_pending_decoration<F, T>(&self, f: F) -> Result<T, ()>
+    where
+        F: FnOnce(&mut Option<Mode>) -> T,
+    {
+        self.with_pending_state(|state| f(&mut state.decoration_mode))
+    }
+
+    /// Returns whether sending a configure right now would actually emit a
+    /// `zxdg_toplevel_decoration_v1.configure` for `mode`, i.e. whether it
+    /// differs from the mode last sent on this toplevel's active decoration
+    /// object.
+    pub(crate) fn decoration_configure_pending(&self, mode: Mode) -> bool {
+        let data = match self.shell_data() {
+            Some(data) => data,
+            None => return false,
+        };
+        let decoration = data.decoration.borrow();
+        let decoration = match decoration.as_ref() {
+            Some(decoration) => decoration,
+            None => return false,
+        };
+
+        let last_sent_mode = decoration
+            .as_ref()
+            .user_data()
+            .get::<RefCell<DecorationState>>()
+            .and_then(|state| state.borrow().last_sent_mode);
+
+        should_send_decoration_configure(last_sent_mode, mode)
+    }
+
+    /// Sends an `xdg_surface.configure` carrying the currently pending state.
+    ///
+    /// If a decoration mode is pending and differs from the last one sent,
+    /// the matching `zxdg_toplevel_decoration_v1.configure` is emitted first,
+    /// under the same serial, as the protocol requires. That part alone is
+    /// skipped when the mode hasn't changed, but the `xdg_surface.configure`
+    /// itself always goes out: other pending state (size, states, ...) must
+    /// still reach the client even when decoration mode didn't move.
+    pub fn send_configure(&self) {
+        let data = match self.shell_data() {
+            Some(data) => data,
+            None => return,
+        };
+
+        let pending = data.pending.borrow().clone();
+
+        if let Some(mode) = pending.decoration_mode {
+            if self.decoration_configure_pending(mode) {
+                if let Some(decoration) = data.decoration.borrow().as_ref() {
+                    send_decoration_configure(decoration, mode);
+                }
+            }
+        }
+
+        let mut serial = data.last_serial.borrow_mut();
+        *serial = serial.wrapping_add(1);
+        let serial = *serial;
+
+        data.xdg_surface.configure(serial);
+        data.configures.borrow_mut().push(Configure { serial, state: pending });
+    }
+
+    /// Handles `xdg_surface.ack_configure`, promoting the acknowledged
+    /// configure's state to [`ToplevelSurface::current_decoration_mode`] and
+    /// friends.
+    pub fn ack_configure(&self, serial: u32) {
+        let data = match self.shell_data() {
+            Some(data) => data,
+            None => return,
+        };
+
+        let mut configures = data.configures.borrow_mut();
+        let acked_index = match configures.iter().position(|c| c.serial == serial) {
+            Some(index) => index,
+            None => return,
+        };
+
+        // Configures older than the acked one will never be acked on their
+        // own (the client acks monotonically), so drop them too.
+        let acked = configures.drain(..=acked_index).last().expect("acked_index is in range");
+        drop(configures);
+
+        *data.current.borrow_mut() = acked.state;
+    }
+
+    /// Returns the decoration mode acknowledged by the client at the last
+    /// `ack_configure`, whichever decoration protocol set it.
+    pub fn current_decoration_mode(&self) -> Option<Mode> {
+        self.shell_data()?.current.borrow().decoration_mode
+    }
+}